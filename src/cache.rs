@@ -0,0 +1,232 @@
+//! On-disk scan cache, modeled on Mercurial's dirstate-v2 docket: a small fixed
+//! header followed by a compact depth-first dump of the scanned tree. Each node
+//! carries the mtime its directory had when it was last scanned, plus each of
+//! its direct files' own size and mtime, so a later run can reuse a directory's
+//! cached entry when its own mtime hasn't moved *and* its files individually
+//! still match (its child directories are still visited and checked the same
+//! way, one by one) — catching a file that grew or shrank in place without
+//! renaming (which wouldn't otherwise bump the parent directory's mtime).
+//!
+//! The cache only remembers names, sizes and mtimes, not per-extension tallies,
+//! so callers that need `--top-exts` output should bypass it and scan fresh.
+
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"WESC";
+const VERSION: u32 = 3;
+
+/// A single cached file's identity: enough to notice if it changed in place
+/// since it was last scanned, even when that doesn't bump its parent
+/// directory's own mtime (e.g. a file growing without being renamed).
+#[derive(Clone)]
+pub struct CacheFile {
+    pub name: OsString,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+#[derive(Clone)]
+pub struct CacheNode {
+    pub name: OsString,
+    pub size: u64,
+    /// This directory's own mtime at scan time, used to detect added/removed entries.
+    pub mtime: u64,
+    pub file_count: u64,
+    pub newest_mtime: u64,
+    pub files: Vec<CacheFile>,
+    pub children: Vec<CacheNode>,
+}
+
+impl CacheFile {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_name(&self.name, w)?;
+        w.write_all(&self.size.to_le_bytes())?;
+        w.write_all(&self.mtime.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<CacheFile> {
+        let name = read_name(r)?;
+        let size = read_u64(r)?;
+        let mtime = read_u64(r)?;
+        Ok(CacheFile { name, size, mtime })
+    }
+}
+
+impl CacheNode {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_name(&self.name, w)?;
+        w.write_all(&self.size.to_le_bytes())?;
+        w.write_all(&self.mtime.to_le_bytes())?;
+        w.write_all(&self.file_count.to_le_bytes())?;
+        w.write_all(&self.newest_mtime.to_le_bytes())?;
+        w.write_all(&(self.files.len() as u32).to_le_bytes())?;
+        for file in &self.files {
+            file.write_to(w)?;
+        }
+        w.write_all(&(self.children.len() as u32).to_le_bytes())?;
+        for child in &self.children {
+            child.write_to(w)?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<CacheNode> {
+        let name = read_name(r)?;
+
+        let size = read_u64(r)?;
+        let mtime = read_u64(r)?;
+        let file_count = read_u64(r)?;
+        let newest_mtime = read_u64(r)?;
+
+        let file_count_cached = read_u32(r)?;
+        let mut files = Vec::with_capacity(file_count_cached as usize);
+        for _ in 0..file_count_cached {
+            files.push(CacheFile::read_from(r)?);
+        }
+
+        let child_count = read_u32(r)?;
+        let mut children = Vec::with_capacity(child_count as usize);
+        for _ in 0..child_count {
+            children.push(CacheNode::read_from(r)?);
+        }
+
+        Ok(CacheNode { name, size, mtime, file_count, newest_mtime, files, children })
+    }
+}
+
+/// A loaded docket: the root tree plus the time the cache was written. `scanned_at`
+/// is carried through for diagnostics/future use; the incremental scan itself
+/// relies on per-node mtimes, not this header timestamp.
+pub struct Docket {
+    #[allow(dead_code)]
+    pub scanned_at: u64,
+    pub tree: CacheNode,
+}
+
+/// Writes `name`'s raw bytes, length-prefixed. On Unix this round-trips any
+/// `OsString` exactly, including non-UTF-8 names; elsewhere names are only
+/// ever UTF-8 to begin with, so there's nothing lossy to worry about.
+fn write_name<W: Write>(name: &OsString, w: &mut W) -> io::Result<()> {
+    #[cfg(unix)]
+    let name_bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        name.as_bytes()
+    };
+    #[cfg(not(unix))]
+    let name_bytes = name.to_string_lossy();
+    #[cfg(not(unix))]
+    let name_bytes = name_bytes.as_bytes();
+
+    w.write_all(&(name_bytes.len() as u16).to_le_bytes())?;
+    w.write_all(name_bytes)
+}
+
+/// Inverse of `write_name`.
+fn read_name<R: Read>(r: &mut R) -> io::Result<OsString> {
+    let name_len = read_u16(r)? as usize;
+    let mut name_buf = vec![0u8; name_len];
+    r.read_exact(&mut name_buf)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(std::ffi::OsStr::from_bytes(&name_buf).to_os_string())
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(OsString::from(String::from_utf8_lossy(&name_buf).into_owned()))
+    }
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Captures the scan options that change what a cached docket's sizes mean.
+/// Folded into the cache key so a `--cache` run under different filtering or
+/// measurement flags is treated as a cold cache rather than silently reusing
+/// sizes that were computed under a different meaning of "size".
+#[derive(Hash)]
+pub struct CacheFingerprint {
+    pub disk_usage: bool,
+    pub no_hidden: bool,
+    pub gitignore: bool,
+    pub count_hardlinks: bool,
+    pub exclude: Vec<String>,
+}
+
+/// Hashes the root path together with the scan options that affect its
+/// meaning, so a docket is only ever trusted for the exact (root, options)
+/// it was written under.
+fn cache_key(root: &Path, fingerprint: &CacheFingerprint) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    root.hash(&mut hasher);
+    fingerprint.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Writes the docket header (magic, format version, cache key, scan
+/// timestamp) followed by the depth-first tree dump.
+pub fn save(
+    path: &Path,
+    root: &Path,
+    fingerprint: &CacheFingerprint,
+    scanned_at: u64,
+    tree: &CacheNode,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    buf.write_all(MAGIC)?;
+    buf.write_all(&VERSION.to_le_bytes())?;
+    buf.write_all(&cache_key(root, fingerprint).to_le_bytes())?;
+    buf.write_all(&scanned_at.to_le_bytes())?;
+    tree.write_to(&mut buf)?;
+    std::fs::write(path, buf)
+}
+
+/// Reads and validates the docket at `path`. Returns `Ok(None)` (rather than an
+/// error) for a missing file or one whose header doesn't match `root` and
+/// `fingerprint` — callers should treat that the same as a cold cache and fall
+/// back to a fresh scan.
+pub fn load(path: &Path, root: &Path, fingerprint: &CacheFingerprint) -> io::Result<Option<Docket>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut cursor = bytes.as_slice();
+
+    let mut magic = [0u8; 4];
+    if cursor.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Ok(None);
+    }
+    if read_u32(&mut cursor)? != VERSION {
+        return Ok(None);
+    }
+    if read_u64(&mut cursor)? != cache_key(root, fingerprint) {
+        return Ok(None);
+    }
+    let scanned_at = read_u64(&mut cursor)?;
+    let tree = CacheNode::read_from(&mut cursor)?;
+
+    Ok(Some(Docket { scanned_at, tree }))
+}
@@ -2,15 +2,29 @@ use std::cmp::Reverse;
 use std::error::Error as StdError;
 use std::ffi::OsString;
 use std::fmt::Display;
+use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use argh::FromArgs;
 use bytesize::ByteSize;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rayon::prelude::*;
 use tabwriter::TabWriter;
-use walkdir::WalkDir;
+
+mod cache;
+use cache::{CacheFile, CacheFingerprint, CacheNode};
+
+/// Name of the on-disk scan cache `--cache` writes into each scanned root.
+/// Always excluded from the walk itself, so a cached run's own docket file
+/// doesn't get counted into the very total it's caching (and so a
+/// `--no-hidden` run doesn't see the total silently shrink once it exists).
+const CACHE_FILE_NAME: &str = ".wes-cache";
 
 #[derive(Debug)]
 enum Error {
@@ -27,51 +41,507 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
+/// Returns a file's logical size, or its allocated disk usage when `disk_usage` is set.
+fn file_size(meta: &fs::Metadata, disk_usage: bool) -> u64 {
+    if !disk_usage {
+        return meta.len();
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        meta.blocks() * 512
+    }
+
+    // No portable allocation-size query exists in std on non-Unix platforms;
+    // fall back to the logical size there.
+    #[cfg(not(unix))]
+    {
+        meta.len()
+    }
+}
+
+/// Bundles the walk-time knobs that `DirTree::scan` needs at every level of recursion.
+#[derive(Clone)]
+struct ScanOptions<'a> {
+    /// The root this scan started from, so `is_excluded` can match `--exclude`
+    /// globs against a path relative to it rather than the full, possibly
+    /// absolute, path.
+    root: &'a Path,
+    disk_usage: bool,
+    exclude: &'a GlobSet,
+    no_hidden: bool,
+    /// One `Gitignore` per directory level between `root` and the directory
+    /// currently being scanned (root's own `.gitignore` first, then one per
+    /// nested `.gitignore` found while descending), each checked in turn so
+    /// a subdirectory's own `.gitignore` rules stack on top of its ancestors'
+    /// rather than replacing them. Empty when `--gitignore` wasn't passed.
+    gitignore_stack: Arc<Vec<Gitignore>>,
+    /// Whether `--gitignore` was passed at all, so descending into a child
+    /// directory can skip even checking for a nested `.gitignore` when it wasn't.
+    gitignore_enabled: bool,
+    duplicate_paths: Option<&'a AHashSet<PathBuf>>,
+}
+
+/// Returns the gitignore stack to use while scanning `dir` and descending into its
+/// children: `parent` (the stack inherited from ancestors) extended with a new
+/// `Gitignore` for `dir`'s own `.gitignore`, if one exists — so a subdirectory's
+/// ignore rules stack on top of its ancestors' instead of replacing them. Returns a
+/// clone of `parent` (cheap: just bumps the `Arc`'s refcount) unchanged when
+/// `--gitignore` wasn't passed, or `dir` has no `.gitignore` of its own.
+fn extend_gitignore_stack(parent: &Arc<Vec<Gitignore>>, dir: &Path, enabled: bool) -> Arc<Vec<Gitignore>> {
+    if !enabled {
+        return Arc::clone(parent);
+    }
+
+    let gitignore_path = dir.join(".gitignore");
+    if !gitignore_path.is_file() {
+        return Arc::clone(parent);
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    builder.add(&gitignore_path);
+    match builder.build() {
+        Ok(gitignore) => {
+            let mut stack = (**parent).clone();
+            stack.push(gitignore);
+            Arc::new(stack)
+        }
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", gitignore_path.display(), e);
+            Arc::clone(parent)
+        }
+    }
+}
+
+/// Returns `opts` with its gitignore stack extended for the child directory at
+/// `path`, so each recursive descent point can pick up that child's own
+/// `.gitignore` (if any) on top of what it inherited.
+fn child_scan_options<'a>(mut opts: ScanOptions<'a>, path: &Path) -> ScanOptions<'a> {
+    opts.gitignore_stack = extend_gitignore_stack(&opts.gitignore_stack, path, opts.gitignore_enabled);
+    opts
+}
+
+/// Returns true if `path` lost the tie-break in `collect_duplicate_paths`, i.e. some
+/// other hardlinked copy of the same inode already claims its bytes.
+fn is_duplicate_path(path: &Path, duplicate_paths: &AHashSet<PathBuf>) -> bool {
+    duplicate_paths.contains(path)
+}
+
+/// Walks every given root to decide — deterministically — which of any
+/// hardlinked duplicates (including ones that span two different roots) should
+/// keep its size: the lexicographically-first path among each inode's copies
+/// always wins. Doing this as its own pass up front (in parallel with rayon,
+/// same as `DirTree::scan`) means the later size-computing walk only needs a
+/// read-only lookup, instead of racing on a shared mutex whose winner depended
+/// on rayon's scheduling and made sizes (and therefore sort order)
+/// nondeterministic across runs. This does mean every file gets stat'd once
+/// here and again during the real scan; parallelizing this pass keeps that
+/// extra cost from being a serial bottleneck in front of the real walk.
+fn collect_duplicate_paths<'a>(roots: impl IntoIterator<Item = (&'a Path, ScanOptions<'a>)>) -> AHashSet<PathBuf> {
+    #[cfg(unix)]
+    {
+        let roots: Vec<_> = roots.into_iter().collect();
+        let by_inode = roots
+            .into_par_iter()
+            .map(|(root, opts)| collect_inodes(root, opts))
+            .reduce(AHashMap::new, |mut acc, partial| {
+                for (inode, paths) in partial {
+                    acc.entry(inode).or_default().extend(paths);
+                }
+                acc
+            });
+
+        let mut duplicate_paths = AHashSet::new();
+        for mut paths in by_inode.into_values() {
+            if paths.len() < 2 {
+                continue;
+            }
+            paths.sort();
+            // Overlapping roots reach the same path more than once; it must still
+            // count as one copy, or it would be marked a duplicate of itself.
+            paths.dedup();
+            if paths.len() < 2 {
+                continue;
+            }
+            duplicate_paths.extend(paths.into_iter().skip(1));
+        }
+        duplicate_paths
+    }
+
+    // (dev, ino) pairs aren't available through std on non-Unix platforms, so every
+    // file is treated as unique there and nothing needs deduping.
+    #[cfg(not(unix))]
+    {
+        let _ = roots.into_iter().count();
+        AHashSet::new()
+    }
+}
+
+#[cfg(unix)]
+fn collect_inodes(path: &Path, opts: ScanOptions) -> AHashMap<(u64, u64), Vec<PathBuf>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut by_inode: AHashMap<(u64, u64), Vec<PathBuf>> = AHashMap::new();
+
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return by_inode,
+    };
+
+    let (dirs, files): (Vec<_>, Vec<_>) = entries
+        .flatten()
+        .filter(|entry| !is_excluded(entry, is_dir_entry(entry), &opts))
+        .partition(is_dir_entry);
+
+    for file in &files {
+        if let Ok(meta) = file.metadata() {
+            by_inode.entry((meta.dev(), meta.ino())).or_default().push(file.path());
+        }
+    }
+
+    let sub_maps: Vec<AHashMap<(u64, u64), Vec<PathBuf>>> = dirs
+        .into_par_iter()
+        .map(|entry| {
+            let child_path = entry.path();
+            let child_opts = child_scan_options(opts.clone(), &child_path);
+            collect_inodes(&child_path, child_opts)
+        })
+        .collect();
+
+    for sub_map in sub_maps {
+        for (inode, paths) in sub_map {
+            by_inode.entry(inode).or_default().extend(paths);
+        }
+    }
+
+    by_inode
+}
+
+/// Returns true if `entry` is itself a directory, without following symlinks —
+/// a symlink to a directory is treated as the (non-recursed) link that it is,
+/// matching the baseline `WalkDir::new` default of not following links. Following
+/// it here would both double-count the target's bytes and risk unbounded
+/// recursion on a symlink cycle.
+fn is_dir_entry(entry: &fs::DirEntry) -> bool {
+    entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false)
+}
+
+/// Returns true if `entry` should be skipped entirely, i.e. never contribute
+/// to `DirTree` sizes or extension tallies.
+fn is_excluded(entry: &fs::DirEntry, is_dir: bool, opts: &ScanOptions) -> bool {
+    if entry.file_name() == CACHE_FILE_NAME {
+        return true;
+    }
+
+    if opts.no_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+        return true;
+    }
+
+    let path = entry.path();
+
+    // Match against both the path relative to the scanned root and the bare
+    // file name, so a basename pattern like `node_modules` excludes it at any
+    // depth without the user having to spell it `**/node_modules`.
+    let relative = path.strip_prefix(opts.root).unwrap_or(&path);
+    if opts.exclude.is_match(relative) || opts.exclude.is_match(entry.file_name()) {
+        return true;
+    }
+
+    // Checked in ancestor-to-descendant order with the last matching rule
+    // winning, so a closer `.gitignore` can override (or `!`-re-include) what an
+    // ancestor ignores, the same way real gitignore semantics allow, while
+    // none of them alone needs to account for rules set further up the tree.
+    let mut ignored = false;
+    for gitignore in opts.gitignore_stack.iter() {
+        let matched = gitignore.matched(&path, is_dir);
+        if matched.is_ignore() {
+            ignored = true;
+        } else if matched.is_whitelist() {
+            ignored = false;
+        }
+    }
+    if ignored {
+        return true;
+    }
+
+    false
+}
+
 struct DirTree {
     name: OsString,
     size: u64,
+    /// Newest mtime of any file found anywhere under this subtree.
+    newest_mtime: u64,
+    /// Cumulative number of files found anywhere under this subtree.
+    file_count: u64,
     children: AHashMap<OsString, DirTree>,
 }
 
 impl DirTree {
     fn new(root: OsString) -> DirTree {
-        DirTree { name: root, size: 0, children: AHashMap::new() }
+        DirTree { name: root, size: 0, newest_mtime: 0, file_count: 0, children: AHashMap::new() }
     }
 
-    fn add_dir(&mut self, path: &Path) {
-        let mut parent = self;
-        for dir in path {
-            parent = parent
-                .children
-                .entry(dir.to_owned())
-                .or_insert_with(|| DirTree::new(dir.to_owned()));
+    /// Recursively scans `path`, building a `DirTree` in parallel with rayon:
+    /// each subdirectory is handed to a worker, and the resulting partial
+    /// trees and extension tallies are merged back into the parent.
+    fn scan(path: &Path, opts: ScanOptions) -> (DirTree, AHashMap<OsString, u64>) {
+        let name = path.file_name().unwrap_or(path.as_os_str()).to_owned();
+        let mut tree = DirTree::new(name);
+        let mut ext_sizes = AHashMap::new();
+
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Unable to read directory structure: {}", e);
+                return (tree, ext_sizes);
+            }
+        };
+
+        let (dirs, files): (Vec<_>, Vec<_>) = entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    eprintln!("Unable to read directory structure: {}", e);
+                    None
+                }
+            })
+            .partition(is_dir_entry);
+
+        let files: Vec<_> = files.into_iter().filter(|entry| !is_excluded(entry, false, &opts)).collect();
+        let dirs: Vec<_> = dirs.into_iter().filter(|entry| !is_excluded(entry, true, &opts)).collect();
+
+        for file in &files {
+            match file.metadata() {
+                Ok(meta) => {
+                    let is_duplicate =
+                        opts.duplicate_paths.is_some_and(|dups| is_duplicate_path(&file.path(), dups));
+                    let size = if is_duplicate { 0 } else { file_size(&meta, opts.disk_usage) };
+                    tree.size += size;
+                    tree.file_count += 1;
+                    tree.newest_mtime = tree.newest_mtime.max(metadata_mtime(&meta));
+                    if let Some(ext) = file.path().extension() {
+                        *ext_sizes.entry(ext.to_owned()).or_insert(0) += size;
+                    }
+                }
+                Err(e) => eprintln!("Unable to read directory structure: {}", e),
+            }
+        }
+
+        let sub_results: Vec<(OsString, DirTree, AHashMap<OsString, u64>)> = dirs
+            .into_par_iter()
+            .map(|entry| {
+                let child_path = entry.path();
+                let child_opts = child_scan_options(opts.clone(), &child_path);
+                let (sub_tree, sub_ext_sizes) = DirTree::scan(&child_path, child_opts);
+                (entry.file_name(), sub_tree, sub_ext_sizes)
+            })
+            .collect();
+
+        for (name, sub_tree, sub_ext_sizes) in sub_results {
+            tree.size += sub_tree.size;
+            tree.file_count += sub_tree.file_count;
+            tree.newest_mtime = tree.newest_mtime.max(sub_tree.newest_mtime);
+            for (ext, size) in sub_ext_sizes {
+                *ext_sizes.entry(ext).or_insert(0) += size;
+            }
+            tree.children.insert(name, sub_tree);
         }
+
+        (tree, ext_sizes)
     }
 
-    fn add_file(&mut self, path: &Path, size: u64) {
-        self.size += size;
+    /// Like `scan`, but consults a previous `--cache` docket: a directory whose
+    /// mtime and direct files still match its cached entry skips the fresh scan of
+    /// its own entries (its directory listing is still read, and its files stat'd,
+    /// to check them against the cache), but every cached child directory is still recursed into and
+    /// validated independently — a change two levels down only bumps *that*
+    /// descendant's own mtime, never this one's, so trusting a whole subtree off
+    /// one top-level match would miss it. Used only when extension tallies
+    /// aren't requested, since the cache doesn't track those separately.
+    fn scan_cached(path: &Path, opts: ScanOptions, cached: Option<&CacheNode>) -> (DirTree, CacheNode) {
+        let name = path.file_name().unwrap_or(path.as_os_str()).to_owned();
+        let mtime = dir_mtime(path).unwrap_or(0);
+
+        if let Some(cached_node) = cached {
+            if cached_node.mtime == mtime {
+                if let Some((own_size, own_file_count, own_newest_mtime)) =
+                    validate_cached_files(path, &opts, &cached_node.files)
+                {
+                    let mut tree = DirTree::new(name.clone());
+                    tree.size = own_size;
+                    tree.file_count = own_file_count;
+                    tree.newest_mtime = own_newest_mtime;
+
+                    let sub_results: Vec<(OsString, DirTree, CacheNode)> = cached_node
+                        .children
+                        .par_iter()
+                        .map(|cached_child| {
+                            let child_path = path.join(&cached_child.name);
+                            let child_opts = child_scan_options(opts.clone(), &child_path);
+                            let (sub_tree, sub_cache) =
+                                DirTree::scan_cached(&child_path, child_opts, Some(cached_child));
+                            (cached_child.name.clone(), sub_tree, sub_cache)
+                        })
+                        .collect();
+
+                    let mut cache_children = Vec::with_capacity(sub_results.len());
+                    for (child_name, sub_tree, sub_cache) in sub_results {
+                        tree.size += sub_tree.size;
+                        tree.file_count += sub_tree.file_count;
+                        tree.newest_mtime = tree.newest_mtime.max(sub_tree.newest_mtime);
+                        cache_children.push(sub_cache);
+                        tree.children.insert(child_name, sub_tree);
+                    }
 
-        let mut parent = self;
-        let mut dirs = path.iter().peekable();
-        while let Some(dir) = dirs.next() {
-            // skip the last Compoenent of the path as it's the file's name
-            if dirs.peek().is_none() {
-                continue;
+                    let cache_node = CacheNode {
+                        name,
+                        size: tree.size,
+                        mtime,
+                        file_count: tree.file_count,
+                        newest_mtime: tree.newest_mtime,
+                        files: cached_node.files.clone(),
+                        children: cache_children,
+                    };
+                    return (tree, cache_node);
+                }
             }
+        }
 
-            parent = parent
-                .children
-                .entry(dir.to_owned())
-                .or_insert_with(|| DirTree::new(dir.to_owned()));
-            parent.size += size;
+        let mut tree = DirTree::new(name.clone());
+
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Unable to read directory structure: {}", e);
+                let cache_node =
+                    CacheNode { name, size: 0, mtime, file_count: 0, newest_mtime: 0, files: Vec::new(), children: Vec::new() };
+                return (tree, cache_node);
+            }
+        };
+
+        let (dirs, files): (Vec<_>, Vec<_>) = entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    eprintln!("Unable to read directory structure: {}", e);
+                    None
+                }
+            })
+            .partition(is_dir_entry);
+
+        let files: Vec<_> = files.into_iter().filter(|entry| !is_excluded(entry, false, &opts)).collect();
+        let dirs: Vec<_> = dirs.into_iter().filter(|entry| !is_excluded(entry, true, &opts)).collect();
+
+        let mut cache_files = Vec::with_capacity(files.len());
+        for file in &files {
+            match file.metadata() {
+                Ok(meta) => {
+                    let is_duplicate =
+                        opts.duplicate_paths.is_some_and(|dups| is_duplicate_path(&file.path(), dups));
+                    tree.size += if is_duplicate { 0 } else { file_size(&meta, opts.disk_usage) };
+                    tree.file_count += 1;
+                    tree.newest_mtime = tree.newest_mtime.max(metadata_mtime(&meta));
+                    cache_files.push(CacheFile { name: file.file_name(), size: meta.len(), mtime: metadata_mtime(&meta) });
+                }
+                Err(e) => eprintln!("Unable to read directory structure: {}", e),
+            }
+        }
+
+        let sub_results: Vec<(OsString, DirTree, CacheNode)> = dirs
+            .into_par_iter()
+            .map(|entry| {
+                let child_path = entry.path();
+                let child_name = entry.file_name();
+                let cached_child = cached.and_then(|c| c.children.iter().find(|child| child.name == child_name));
+                let child_opts = child_scan_options(opts.clone(), &child_path);
+                let (sub_tree, sub_cache) = DirTree::scan_cached(&child_path, child_opts, cached_child);
+                (child_name, sub_tree, sub_cache)
+            })
+            .collect();
+
+        let mut cache_children = Vec::with_capacity(sub_results.len());
+        for (child_name, sub_tree, sub_cache) in sub_results {
+            tree.size += sub_tree.size;
+            tree.file_count += sub_tree.file_count;
+            tree.newest_mtime = tree.newest_mtime.max(sub_tree.newest_mtime);
+            cache_children.push(sub_cache);
+            tree.children.insert(child_name, sub_tree);
+        }
+
+        let cache_node = CacheNode {
+            name,
+            size: tree.size,
+            mtime,
+            file_count: tree.file_count,
+            newest_mtime: tree.newest_mtime,
+            files: cache_files,
+            children: cache_children,
+        };
+        (tree, cache_node)
+    }
+}
+
+/// Validates that every file `scan_cached` previously cached directly under
+/// `path` still has the same size and mtime — a file can grow or shrink in
+/// place without its parent directory's own mtime moving, so the directory-mtime
+/// check alone would let that go unnoticed. On success, returns this directory's
+/// own-file totals (size, file count, newest mtime) recomputed under the current
+/// scan options, so the caller can skip re-reading and re-stat'ing them from
+/// scratch. Returns `None` if a cached file is missing, changed, or a new one
+/// appeared, in which case the whole directory should be treated as a cache miss.
+fn validate_cached_files(path: &Path, opts: &ScanOptions, cached_files: &[CacheFile]) -> Option<(u64, u64, u64)> {
+    let entries = fs::read_dir(path).ok()?;
+
+    let mut size = 0u64;
+    let mut file_count = 0u64;
+    let mut newest_mtime = 0u64;
+    let mut seen = 0usize;
+
+    for entry in entries.flatten() {
+        let is_dir = is_dir_entry(&entry);
+        if is_excluded(&entry, is_dir, opts) {
+            continue;
+        }
+        if is_dir {
+            continue;
         }
+
+        let cached_file = cached_files.iter().find(|f| f.name == entry.file_name())?;
+        let meta = entry.metadata().ok()?;
+        if meta.len() != cached_file.size || metadata_mtime(&meta) != cached_file.mtime {
+            return None;
+        }
+
+        let is_duplicate = opts.duplicate_paths.is_some_and(|dups| is_duplicate_path(&entry.path(), dups));
+        size += if is_duplicate { 0 } else { file_size(&meta, opts.disk_usage) };
+        file_count += 1;
+        newest_mtime = newest_mtime.max(metadata_mtime(&meta));
+        seen += 1;
+    }
+
+    if seen != cached_files.len() {
+        return None;
     }
+
+    Some((size, file_count, newest_mtime))
+}
+
+fn metadata_mtime(meta: &fs::Metadata) -> u64 {
+    meta.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn dir_mtime(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().map(|meta| metadata_mtime(&meta))
 }
 
 #[derive(Copy, Clone, Debug)]
 enum SortBy {
     Name,
     Size,
+    Date,
+    Count,
 }
 
 impl FromStr for SortBy {
@@ -81,6 +551,8 @@ impl FromStr for SortBy {
         match s {
             "name" => Ok(Self::Name),
             "size" => Ok(Self::Size),
+            "date" => Ok(Self::Date),
+            "count" => Ok(Self::Count),
             _ => Err(Error::InvalidSort(s.to_owned())),
         }
     }
@@ -93,7 +565,7 @@ struct Opts {
     #[argh(option, short = 'e', long = "top-exts")]
     top_exts: Option<usize>,
 
-    /// how to sort the results (size, name) [default: size]
+    /// how to sort the results (size, name, date, count) [default: size]
     #[argh(option, short = 's', long = "sort", default = "SortBy::Size")]
     sort_by: SortBy,
 
@@ -101,8 +573,51 @@ struct Opts {
     #[argh(switch, short = 'r', long = "reverse")]
     reverse: bool,
 
-    #[argh(positional, default = r#"".".into()"#)]
-    root: PathBuf,
+    /// how many levels of subdirectories to print [default: 1]
+    #[argh(option, short = 'd', long = "depth", default = "1")]
+    depth: usize,
+
+    /// collapse subtrees smaller than this size into a combined `<...>` entry (e.g. `1M`)
+    #[argh(option, long = "aggregate")]
+    aggregate: Option<ByteSize>,
+
+    /// report allocated disk usage (blocks on disk) instead of apparent file size
+    #[argh(switch, long = "disk-usage")]
+    disk_usage: bool,
+
+    /// exclude entries whose path matches this glob pattern (repeatable)
+    #[argh(option, long = "exclude")]
+    exclude: Vec<String>,
+
+    /// skip hidden files and directories (dotfiles)
+    #[argh(switch, long = "no-hidden")]
+    no_hidden: bool,
+
+    /// honor .gitignore rules, including nested .gitignore files found in
+    /// subdirectories while scanning
+    #[argh(switch, long = "gitignore")]
+    gitignore: bool,
+
+    /// count every hardlink toward the total instead of deduping by inode
+    #[argh(switch, long = "count-hardlinks")]
+    count_hardlinks: bool,
+
+    /// cache each root's scan in a `.wes-cache` file and reuse it on unchanged
+    /// subtrees next run (ignored together with --top-exts)
+    #[argh(switch, long = "cache")]
+    cache: bool,
+
+    /// one or more directories to scan and compare side by side [default: .]
+    #[argh(positional)]
+    roots: Vec<PathBuf>,
+}
+
+/// Builds the initial gitignore stack for `root`: just its own `.gitignore`, if
+/// `--gitignore` was passed and one exists. Nested `.gitignore`s in
+/// subdirectories are appended later, one per level, as `scan`/`scan_cached`/
+/// `collect_inodes` descend — see `extend_gitignore_stack`.
+fn load_gitignore(root: &Path, enabled: bool) -> Arc<Vec<Gitignore>> {
+    extend_gitignore_stack(&Arc::new(Vec::new()), root, enabled)
 }
 
 fn main() {
@@ -117,29 +632,88 @@ fn main() {
 fn run() -> Result<(), Box<dyn StdError>> {
     let opts: Opts = argh::from_env();
 
-    let mut dir_tree = DirTree::new(opts.root.clone().into());
+    let mut exclude_builder = GlobSetBuilder::new();
+    for pattern in &opts.exclude {
+        exclude_builder.add(Glob::new(pattern)?);
+    }
+    let exclude = exclude_builder.build()?;
+
+    let roots = if opts.roots.is_empty() { vec![PathBuf::from(".")] } else { opts.roots.clone() };
+
+    let fingerprint = CacheFingerprint {
+        disk_usage: opts.disk_usage,
+        no_hidden: opts.no_hidden,
+        gitignore: opts.gitignore,
+        count_hardlinks: opts.count_hardlinks,
+        exclude: opts.exclude.clone(),
+    };
+
+    // Decide, once and up front, which hardlinked duplicates should count as 0
+    // bytes — the lexicographically-first path among each inode's copies always
+    // wins. Doing this before the parallel scan (rather than racing on a shared
+    // mutex during it) keeps sizes, and therefore sort order, deterministic
+    // across runs.
+    let duplicate_paths: Option<AHashSet<PathBuf>> = if opts.count_hardlinks {
+        None
+    } else {
+        let precheck_roots = roots.iter().map(|root| {
+            let scan_opts = ScanOptions {
+                root,
+                disk_usage: opts.disk_usage,
+                exclude: &exclude,
+                no_hidden: opts.no_hidden,
+                gitignore_stack: load_gitignore(root, opts.gitignore),
+                gitignore_enabled: opts.gitignore,
+                duplicate_paths: None,
+            };
+            (root.as_path(), scan_opts)
+        });
+        Some(collect_duplicate_paths(precheck_roots))
+    };
+
+    // Scan every root independently, then fold each one's top-level entries into a
+    // single synthetic tree so they can be sorted and compared side by side. With
+    // only one root there's nothing to total across, so keep the grand-total row
+    // labeled with that root (matching plain `wes <dir>` output) rather than the
+    // literal "total", which only makes sense once multiple roots are combined.
+    let total_label =
+        if roots.len() == 1 { roots[0].clone().into_os_string() } else { OsString::from("total") };
+    let mut combined_tree = DirTree::new(total_label);
     let mut ext_sizes: AHashMap<OsString, u64> = AHashMap::new();
 
-    for entry in WalkDir::new(&opts.root) {
-        match entry {
-            Ok(entry) => {
-                let path = entry.path().strip_prefix(&opts.root)?;
-                let meta = entry.metadata()?;
-
-                if meta.is_dir() {
-                    dir_tree.add_dir(path);
-                } else {
-                    let size = meta.len();
-                    dir_tree.add_file(path, size);
-                    if let Some(ext) = path.extension() {
-                        let total = ext_sizes.entry(ext.to_owned()).or_insert(0);
-                        *total += size;
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Unable to read directory structure: {}", e);
+    for root in &roots {
+        let scan_opts = ScanOptions {
+            root,
+            disk_usage: opts.disk_usage,
+            exclude: &exclude,
+            no_hidden: opts.no_hidden,
+            gitignore_stack: load_gitignore(root, opts.gitignore),
+            gitignore_enabled: opts.gitignore,
+            duplicate_paths: duplicate_paths.as_ref(),
+        };
+
+        let (root_tree, root_ext_sizes) = if opts.cache && opts.top_exts.is_none() {
+            let cache_path = root.join(CACHE_FILE_NAME);
+            let docket = cache::load(&cache_path, root, &fingerprint)?;
+            let (root_tree, cache_node) = DirTree::scan_cached(root, scan_opts, docket.as_ref().map(|d| &d.tree));
+            let scanned_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            if let Err(e) = cache::save(&cache_path, root, &fingerprint, scanned_at, &cache_node) {
+                eprintln!("Unable to write scan cache: {}", e);
             }
+            (root_tree, AHashMap::new())
+        } else {
+            DirTree::scan(root, scan_opts)
+        };
+        combined_tree.size += root_tree.size;
+
+        for (ext, size) in root_ext_sizes {
+            *ext_sizes.entry(ext).or_insert(0) += size;
+        }
+
+        for mut child in root_tree.children.into_values() {
+            let full_path = root.join(&child.name).into_os_string();
+            child.name = full_path.clone();
+            combined_tree.children.insert(full_path, child);
         }
     }
 
@@ -149,7 +723,14 @@ fn run() -> Result<(), Box<dyn StdError>> {
         println!();
     }
 
-    print_space_usage(&opts.root, dir_tree, opts.sort_by, opts.reverse)?;
+    print_space_usage(
+        Path::new(""),
+        combined_tree,
+        opts.sort_by,
+        opts.reverse,
+        opts.depth,
+        opts.aggregate.map(|size| size.0),
+    )?;
 
     Ok(())
 }
@@ -178,38 +759,92 @@ fn print_top_extensions(
     Ok(())
 }
 
+fn format_size(size: u64) -> String {
+    format!("{: >10}", ByteSize::b(size).to_string().replace(" B", "  B"))
+}
+
+/// Bundles the knobs that stay constant across one `print_dir_tree` recursion.
+#[derive(Copy, Clone)]
+struct PrintOptions {
+    sort_by: SortBy,
+    reverse: bool,
+    aggregate: Option<u64>,
+}
+
+fn sorted_children(dir_tree: &DirTree, sort_by: SortBy, reverse: bool) -> Vec<&DirTree> {
+    let mut children = dir_tree.children.values().collect::<Vec<_>>();
+    match sort_by {
+        SortBy::Name if reverse => children.sort_by_key(|d| Reverse(d.name.clone())),
+        SortBy::Name => children.sort_by_key(|d| d.name.clone()),
+        SortBy::Size if reverse => children.sort_by_key(|d| Reverse(d.size)),
+        SortBy::Size => children.sort_by_key(|d| d.size),
+        SortBy::Date if reverse => children.sort_by_key(|d| Reverse(d.newest_mtime)),
+        SortBy::Date => children.sort_by_key(|d| d.newest_mtime),
+        SortBy::Count if reverse => children.sort_by_key(|d| Reverse(d.file_count)),
+        SortBy::Count => children.sort_by_key(|d| d.file_count),
+    }
+    children
+}
+
+/// Prints the children of `dir_tree`, descending up to `remaining_depth` levels and
+/// collapsing any subtree smaller than `opts.aggregate` into a single `<...>` entry per parent.
+fn print_dir_tree(
+    tw: &mut TabWriter<Vec<u8>>,
+    root: &Path,
+    dir_tree: &DirTree,
+    opts: PrintOptions,
+    remaining_depth: usize,
+    indent: usize,
+) -> Result<(), Box<dyn StdError>> {
+    let children = sorted_children(dir_tree, opts.sort_by, opts.reverse);
+    let mut others = 0u64;
+
+    for child in &children {
+        if let Some(threshold) = opts.aggregate {
+            if child.size < threshold {
+                others += child.size;
+                continue;
+            }
+        }
+
+        let name = if indent == 0 {
+            root.join(&child.name).to_string_lossy().into_owned()
+        } else {
+            format!("{}{}", "  ".repeat(indent), child.name.to_string_lossy())
+        };
+        writeln!(tw, "{}\t{}", format_size(child.size), name)?;
+
+        if remaining_depth > 1 {
+            print_dir_tree(tw, root, child, opts, remaining_depth - 1, indent + 1)?;
+        }
+    }
+
+    if others > 0 {
+        writeln!(tw, "{}\t{}<...>", format_size(others), "  ".repeat(indent + 1))?;
+    }
+
+    Ok(())
+}
+
 fn print_space_usage(
     root: &Path,
     dir_tree: DirTree,
     sort_by: SortBy,
     reverse: bool,
+    depth: usize,
+    aggregate: Option<u64>,
 ) -> Result<(), Box<dyn StdError>> {
     let mut tw = TabWriter::new(vec![]);
-    let mut directories = dir_tree.children.values().collect::<Vec<_>>();
-    match sort_by {
-        SortBy::Name if reverse => directories.sort_by_key(|d| Reverse(d.name.clone())),
-        SortBy::Name => directories.sort_by_key(|d| d.name.clone()),
-        SortBy::Size if reverse => directories.sort_by_key(|d| Reverse(d.size)),
-        SortBy::Size => directories.sort_by_key(|d| d.size),
-    }
-
-    for dir in &directories {
-        let size_str = format!("{: >10}", ByteSize::b(dir.size).to_string().replace(" B", "  B"));
-        writeln!(
-            &mut tw,
-            "{}\t{}",
-            format!("{: >8}", size_str),
-            root.join(dir.name.clone()).to_string_lossy()
-        )?;
-    }
+    let has_children = !dir_tree.children.is_empty();
+    let print_opts = PrintOptions { sort_by, reverse, aggregate };
 
-    let size_str = format!("{: >10}", ByteSize::b(dir_tree.size).to_string().replace(" B", "  B"));
+    print_dir_tree(&mut tw, root, &dir_tree, print_opts, depth, 0)?;
 
-    if !directories.is_empty() {
+    if has_children {
         writeln!(&mut tw, "----------")?;
     }
 
-    writeln!(&mut tw, "{:>8}\t{}", format!("{: >8}", size_str), dir_tree.name.to_string_lossy())?;
+    writeln!(&mut tw, "{:>8}\t{}", format!("{: >8}", format_size(dir_tree.size)), dir_tree.name.to_string_lossy())?;
     tw.flush()?;
     io::stdout().write_all(&tw.into_inner()?)?;
 